@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+// Thin wrapper around a parsed DBC file: resolves message names to CAN IDs for the
+// --can-filter allow/deny list, and decodes a raw CAN payload into a name -> physical
+// value map for each signal the DBC defines on that message. The qlog schema itself has
+// no field for decoded signals (openpilot logs raw CAN and leaves decoding to replay
+// time; Cabana does its own decoding at replay from the --dbc path this tool passes
+// through to the generated launch script), so `decode` is instead used to write a
+// per-segment signals.csv sidecar (see encode_segment in main.rs) for verifying a DBC
+// against the log without waiting on a Cabana replay.
+pub struct MessageDb {
+    // CAN ID by message name, lowercased for case-insensitive lookup.
+    ids_by_name: HashMap<String, u32>,
+    signals_by_id: HashMap<u32, Vec<Signal>>,
+}
+
+// Bit layout needed to pull one signal's raw value out of a CAN payload.
+struct Signal {
+    name: String,
+    start_bit: u64,
+    size: u64,
+    big_endian: bool,
+    signed: bool,
+    factor: f64,
+    offset: f64,
+}
+
+impl MessageDb {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = std::fs::read(path)?;
+        let dbc = can_dbc::DBC::from_slice(&contents)
+            .map_err(|e| format!("Failed to parse DBC {path:?}: {e:?}"))?;
+
+        let mut ids_by_name = HashMap::new();
+        let mut signals_by_id = HashMap::new();
+
+        for message in dbc.messages() {
+            let can_id = message.message_id().0;
+            ids_by_name.insert(message.message_name().to_lowercase(), can_id);
+
+            let signals = message
+                .signals()
+                .iter()
+                .map(|s| Signal {
+                    name: s.name().clone(),
+                    start_bit: s.start_bit(),
+                    size: s.signal_size(),
+                    big_endian: matches!(s.byte_order(), can_dbc::ByteOrder::BigEndian),
+                    signed: matches!(s.value_type(), can_dbc::ValueType::Signed),
+                    factor: s.factor(),
+                    offset: s.offset(),
+                })
+                .collect();
+            signals_by_id.insert(can_id, signals);
+        }
+
+        Ok(Self {
+            ids_by_name,
+            signals_by_id,
+        })
+    }
+
+    pub fn id_for_name(&self, name: &str) -> Option<u32> {
+        self.ids_by_name.get(&name.to_lowercase()).copied()
+    }
+
+    // Decode every signal this DBC defines on `can_id` out of `data`, keyed by signal
+    // name, with factor/offset already applied to give each a physical value. A CAN ID
+    // this DBC doesn't define, or a signal whose bit range runs past the end of `data`,
+    // is simply absent from the result.
+    pub fn decode(&self, can_id: u32, data: &[u8]) -> HashMap<String, f64> {
+        let Some(signals) = self.signals_by_id.get(&can_id) else {
+            return HashMap::new();
+        };
+
+        signals
+            .iter()
+            .filter_map(|sig| sig.extract(data).map(|value| (sig.name.clone(), value)))
+            .collect()
+    }
+}
+
+impl Signal {
+    // Bit numbering follows the DBC convention: Intel (little-endian) signals number
+    // bits physically (byte 0 holds bits 0-7, LSB first, `start_bit` is the signal's
+    // LSB); Motorola (big-endian) signals number bits in a single MSB-first stream that
+    // runs through each byte before continuing into the next, so `start_bit` there is
+    // the signal's MSB.
+    fn extract(&self, data: &[u8]) -> Option<f64> {
+        if self.size == 0 || self.size > 64 {
+            return None;
+        }
+
+        let mut raw: u64 = 0;
+        if self.big_endian {
+            for k in 0..self.size {
+                let motorola_bit = self.start_bit + k;
+                let phys_bit = (motorola_bit / 8) * 8 + (7 - motorola_bit % 8);
+                raw = (raw << 1) | get_bit(data, phys_bit)?;
+            }
+        } else {
+            for k in (0..self.size).rev() {
+                let phys_bit = self.start_bit + k;
+                raw = (raw << 1) | get_bit(data, phys_bit)?;
+            }
+        }
+
+        let value = if self.signed && self.size < 64 {
+            let sign_bit = 1u64 << (self.size - 1);
+            if raw & sign_bit != 0 {
+                (raw as i64 - (1i64 << self.size)) as f64
+            } else {
+                raw as f64
+            }
+        } else {
+            raw as f64
+        };
+
+        Some(value * self.factor + self.offset)
+    }
+}
+
+// Read bit `bit_idx` (0 = LSB of byte 0) out of `data`, or None if it's out of range.
+fn get_bit(data: &[u8], bit_idx: u64) -> Option<u64> {
+    let byte = data.get((bit_idx / 8) as usize)?;
+    Some(u64::from((byte >> (bit_idx % 8)) & 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Signal;
+
+    fn signal(start_bit: u64, size: u64, big_endian: bool, signed: bool) -> Signal {
+        Signal {
+            name: "test".to_string(),
+            start_bit,
+            size,
+            big_endian,
+            signed,
+            factor: 1.0,
+            offset: 0.0,
+        }
+    }
+
+    #[test]
+    fn intel_signal_is_little_endian() {
+        let sig = signal(0, 16, false, false);
+        assert_eq!(sig.extract(&[0x34, 0x12]), Some(4660.0));
+    }
+
+    #[test]
+    fn motorola_signal_is_big_endian() {
+        let sig = signal(0, 16, true, false);
+        assert_eq!(sig.extract(&[0x12, 0x34]), Some(4660.0));
+    }
+
+    #[test]
+    fn signed_value_wraps_around_sign_bit() {
+        let sig = signal(0, 8, false, true);
+        assert_eq!(sig.extract(&[0xff]), Some(-1.0));
+    }
+
+    #[test]
+    fn factor_and_offset_are_applied() {
+        let mut sig = signal(0, 8, false, false);
+        sig.factor = 0.5;
+        sig.offset = 2.0;
+        assert_eq!(sig.extract(&[10]), Some(7.0));
+    }
+
+    #[test]
+    fn bit_range_past_data_end_is_none() {
+        let sig = signal(0, 16, false, false);
+        assert_eq!(sig.extract(&[0x34]), None);
+    }
+}
@@ -0,0 +1,75 @@
+use anyhow::{Context, Result};
+use ffmpeg::{format, media, Dictionary};
+use std::path::{Path, PathBuf};
+
+// Concatenate a route's per-segment fcamera.ts videos into a single fast-start MP4,
+// so a route can be shared or scrubbed without installing Cabana.
+//
+// Segment videos are demuxed and remuxed in order, with packet timestamps rebased to
+// stay contiguous across segment boundaries, into one mp4 output written with the
+// `faststart` movflag so ffmpeg moves the moov atom ahead of mdat once muxing is done.
+pub fn concat_segments_to_mp4(segment_videos: &[PathBuf], output_path: &Path) -> Result<()> {
+    if segment_videos.is_empty() {
+        anyhow::bail!("No segment videos to export");
+    }
+
+    let mut octx = format::output(output_path)
+        .with_context(|| format!("Failed to create output context for {:?}", output_path))?;
+
+    let mut out_stream_index = None;
+    let mut ts_offset: i64 = 0;
+
+    for (seg_idx, segment_video) in segment_videos.iter().enumerate() {
+        let mut ictx = format::input(segment_video)
+            .with_context(|| format!("Failed to open segment video {:?}", segment_video))?;
+
+        let in_stream_index = ictx
+            .streams()
+            .best(media::Type::Video)
+            .ok_or(ffmpeg::Error::StreamNotFound)
+            .with_context(|| format!("{:?} has no video stream", segment_video))?
+            .index();
+
+        if out_stream_index.is_none() {
+            let in_stream = ictx.stream(in_stream_index).unwrap();
+            let mut ost = octx.add_stream()?;
+            ost.set_parameters(in_stream.parameters());
+            out_stream_index = Some(ost.index());
+
+            let mut movflags = Dictionary::new();
+            movflags.set("movflags", "faststart");
+            octx.write_header_with(movflags)
+                .context("Failed to write MP4 header")?;
+        }
+
+        let mut max_ts = ts_offset;
+
+        for (stream, mut packet) in ictx.packets() {
+            if stream.index() != in_stream_index {
+                continue;
+            }
+
+            if let Some(pts) = packet.pts() {
+                let rebased = pts + ts_offset;
+                packet.set_pts(Some(rebased));
+                max_ts = max_ts.max(rebased);
+            }
+            if let Some(dts) = packet.dts() {
+                packet.set_dts(Some(dts + ts_offset));
+            }
+
+            packet.set_stream(out_stream_index.expect("output stream set above"));
+            packet
+                .write_interleaved(&mut octx)
+                .context("Failed to write MP4 packet")?;
+        }
+
+        eprintln!("Appended segment {seg_idx} ({segment_video:?}) to {output_path:?}");
+        ts_offset = max_ts + 1;
+    }
+
+    octx.write_trailer().context("Failed to write MP4 trailer")?;
+    eprintln!("Wrote fast-start MP4 to {output_path:?}");
+
+    Ok(())
+}
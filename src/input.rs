@@ -1,12 +1,36 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::error::Error;
+use std::fmt;
 use std::path::Path;
 
-use itertools::Itertools;
 use serde::Deserialize;
 
+use crate::dbc::MessageDb;
 use crate::video::SourceFrame;
 use crate::Nanos;
 
+// A non-fatal error hit while ingesting a CAN log row/line. Collected rather
+// than aborting the whole route build, so a log with occasional corruption
+// can still be salvaged. `record` is the offending row/line rendered as text,
+// regardless of the source format.
+#[derive(Debug)]
+pub struct ParseError {
+    pub row: usize,
+    pub record: String,
+    pub error: Box<dyn Error>,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "row {}: {} (record: {:?})",
+            self.row, self.error, self.record
+        )
+    }
+}
+
 // Wrapper enum for all inputs to the route log
 #[derive(Eq)]
 pub enum LogInput {
@@ -24,6 +48,31 @@ impl LogInput {
             LogInput::Alert(s) => s.timestamp,
         }
     }
+
+    // Shift this input's timestamp by `delta` nanoseconds.
+    fn rebase(&mut self, delta: Nanos) {
+        match self {
+            LogInput::CAN(m) => m.timestamp += delta,
+            LogInput::Frame(s) => s.ts_ns += delta,
+            LogInput::Alert(s) => s.timestamp += delta,
+        }
+    }
+}
+
+// Restrict `inputs` to the [start, end) window of nanosecond timestamps, and shift
+// the survivors so the trimmed route once again starts at t=0 — the same rebasing
+// `sync.can_ts_offs()` already does to line CAN timestamps up with the video's pts=0.
+pub fn trim_and_rebase(
+    inputs: impl Iterator<Item = LogInput>,
+    start: Nanos,
+    end: Option<Nanos>,
+) -> impl Iterator<Item = LogInput> {
+    inputs
+        .filter(move |i| i.timestamp() >= start && end.map_or(true, |end| i.timestamp() < end))
+        .map(move |mut i| {
+            i.rebase(-start);
+            i
+        })
 }
 
 impl From<CANMessage> for LogInput {
@@ -78,6 +127,20 @@ impl PartialOrd for CANMessage {
     }
 }
 
+// Which on-disk format a CAN log is stored in. The CSV format is this tool's
+// own original export format; the other two are read so logs captured
+// directly off a car's CAN bus can be used without converting them first.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum CanLogFormat {
+    #[default]
+    Csv,
+    // Linux-can-utils `candump -l` log, e.g. `(1613999477.123456) can0 18FEF100#0011223344556677`
+    Candump,
+    // Vector ASC (ASCII) trace, e.g. `1.234567 1  123             Rx   d 8 11 22 33 44 55 66 77 88`
+    VectorAsc,
+}
+
 impl CANMessage {
     // TODO: improve error propagation
     pub fn parse_from(record: csv::StringRecord, ts_offs: Nanos) -> Result<Self, Box<dyn Error>> {
@@ -111,32 +174,376 @@ impl CANMessage {
     pub fn timestamp(&self) -> Nanos {
         self.timestamp
     }
+
+    // Parse one line of `candump -l` output, e.g.
+    //   (1613999477.123456) can0 18FEF100#0011223344556677
+    //
+    // The bus number comes from looking the interface name up in `interface_bus`
+    // (populated from --can-interface-bus); interfaces not listed there fall back to
+    // the trailing digits of their name (so "can1" is bus 1), or bus 0 if there are none.
+    pub fn parse_candump_line(
+        line: &str,
+        ts_offs: Nanos,
+        interface_bus: &HashMap<String, u8>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut fields = line.split_whitespace();
+
+        let ts_field = fields.next().ok_or("missing timestamp field")?;
+        let ts_s: f64 = ts_field
+            .strip_prefix('(')
+            .and_then(|s| s.strip_suffix(')'))
+            .ok_or("timestamp not wrapped in parentheses")?
+            .parse()?;
+
+        let interface = fields.next().ok_or("missing interface field")?;
+        let bus_no = interface_bus.get(interface).copied().unwrap_or_else(|| {
+            interface
+                .trim_start_matches(|c: char| !c.is_ascii_digit())
+                .parse()
+                .unwrap_or(0)
+        });
+
+        let frame = fields.next().ok_or("missing CAN frame field")?;
+        let (id_str, data_str) = frame
+            .split_once('#')
+            .ok_or("CAN frame field missing '#' separator")?;
+
+        let can_id = u32::from_str_radix(id_str, 16)?;
+        let is_extended_id = id_str.len() > 3;
+
+        let data = data_str
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| -> Result<u8, Box<dyn Error>> {
+                let s = std::str::from_utf8(pair)?;
+                Ok(u8::from_str_radix(s, 16)?)
+            })
+            .collect::<Result<Vec<u8>, Box<dyn Error>>>()?;
+
+        Ok(CANMessage {
+            timestamp: (ts_s * 1_000_000_000.0) as Nanos - ts_offs,
+            can_id,
+            is_extended_id,
+            bus_no,
+            data,
+        })
+    }
+
+    // Parse one data-frame line of a Vector ASC (ASCII) trace, e.g.
+    //   1.234567 1  123             Rx   d 8 11 22 33 44 55 66 77 88
+    //
+    // Only plain data frames are handled here; error frames, remote frames and
+    // the various bus-statistics line formats ASC also allows are not recognised.
+    pub fn parse_asc_line(line: &str, ts_offs: Nanos) -> Result<Self, Box<dyn Error>> {
+        let mut fields = line.split_whitespace();
+
+        let ts_s: f64 = fields.next().ok_or("missing timestamp field")?.parse()?;
+        let channel: u8 = fields.next().ok_or("missing channel field")?.parse()?;
+        let id_field = fields.next().ok_or("missing CAN ID field")?;
+        let _direction = fields.next().ok_or("missing direction field")?; // Rx/Tx, not tracked
+        let frame_type = fields.next().ok_or("missing frame type field")?;
+        if frame_type != "d" {
+            return Err(format!("unsupported ASC frame type {frame_type:?}").into());
+        }
+        let _dlc = fields.next().ok_or("missing DLC field")?;
+
+        let is_extended_id = id_field.ends_with('x');
+        let can_id = u32::from_str_radix(id_field.trim_end_matches('x'), 16)?;
+
+        let data = fields
+            .map(|b| u8::from_str_radix(b, 16))
+            .collect::<Result<Vec<u8>, _>>()?;
+
+        Ok(CANMessage {
+            timestamp: (ts_s * 1_000_000_000.0) as Nanos - ts_offs,
+            can_id,
+            is_extended_id,
+            bus_no: channel.saturating_sub(1), // ASC channels are numbered from 1
+            data,
+        })
+    }
 }
 
-pub fn read_can_messages(
+// Messages from different CAN buses can arrive slightly out of order relative to one
+// another in a log (each bus's own messages are in order, but interleaving >1 bus isn't
+// guaranteed to be). Rather than buffering every message and calling .sorted() once the
+// whole log is read, this buffer does a streaming k-way merge: it holds only a small
+// bounded window of the most recent messages and emits the earliest one as each new
+// message arrives, so sorting itself only ever needs O(window) messages at a time
+// instead of O(n log n) over the whole log. A bus that falls behind the others by more
+// than `window` messages can still end up slightly out of order in the output.
+//
+// This bounds the *reordering* window, not the CAN log's own memory use: the readers
+// below still collect this buffer's output into one `Vec<CANMessage>` before returning,
+// since find_missing_can_messages() and --can-filter both need the full per-route CAN
+// message list. main.rs's segment/video/alert bucketing downstream of this, which used
+// to materialize the *entire* route's inputs into a BTreeMap up front regardless of how
+// the CAN log was read, now streams segments out to the worker pool as soon as the
+// merged input stream moves past them, so that larger blowup no longer applies.
+struct ReorderBuffer {
+    window: usize,
+    heap: BinaryHeap<Reverse<CANMessage>>,
+}
+
+impl ReorderBuffer {
+    fn new(window: usize) -> Self {
+        Self {
+            window,
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    fn push(&mut self, msg: CANMessage) -> Option<CANMessage> {
+        self.heap.push(Reverse(msg));
+        if self.heap.len() > self.window {
+            self.heap.pop().map(|Reverse(m)| m)
+        } else {
+            None
+        }
+    }
+
+    // Drain whatever is left once the source is exhausted, earliest first.
+    fn finish(mut self) -> impl Iterator<Item = CANMessage> {
+        std::iter::from_fn(move || self.heap.pop().map(|Reverse(m)| m))
+    }
+}
+
+// How many CAN messages to hold in the reorder buffer's lookahead window.
+const REORDER_WINDOW: usize = 1024;
+
+// How often to print progress while reading a CAN log, in rows/lines.
+const PROGRESS_INTERVAL: usize = 500_000;
+
+fn read_can_messages_csv(
     csv_log_path: &Path,
     can_ts_offs: Nanos,
-) -> Result<Vec<CANMessage>, Box<dyn Error>> {
-    eprintln!("Opening CAN log {:?}...", csv_log_path);
-
+    strict: bool,
+) -> Result<(Vec<CANMessage>, Vec<ParseError>), Box<dyn Error>> {
     let mut rdr = csv::ReaderBuilder::new()
         .flexible(true)
         .from_path(csv_log_path)?;
 
-    Ok(rdr
-        .records()
-        .map(|r| match r {
-            Ok(r) => CANMessage::parse_from(r, can_ts_offs),
-            Err(e) => panic!("Error reading CSV file: {}", e), // TODO: error handling!
-        })
-        .map(|m| m.unwrap()) // TODO: more error handling!
+    let mut messages = vec![];
+    let mut errors = vec![];
+    let mut reorder = ReorderBuffer::new(REORDER_WINDOW);
+    let started = std::time::Instant::now();
+
+    for (row, result) in rdr.records().enumerate() {
+        let record = result?; // A genuinely broken CSV reader is still fatal
+
+        match CANMessage::parse_from(record.clone(), can_ts_offs) {
+            Ok(m) => messages.extend(reorder.push(m)),
+            Err(error) if strict => {
+                return Err(format!("row {row}: {error} (record: {record:?})").into())
+            }
+            Err(error) => errors.push(ParseError {
+                row,
+                // Join the raw fields rather than `format!("{record:?}")`: ParseError's
+                // Display impl already wraps `record` in `{:?}`, so pre-Debug-formatting it
+                // here would double-escape quotes/backslashes in warnings.txt.
+                record: record.iter().collect::<Vec<_>>().join(","),
+                error,
+            }),
+        }
+
+        if row > 0 && row % PROGRESS_INTERVAL == 0 {
+            log_progress("rows", row, started);
+        }
+    }
+    messages.extend(reorder.finish());
+
+    Ok((messages, errors))
+}
+
+// Print a "still working" line with elapsed time and unit-per-second throughput, for
+// the long CAN logs where PROGRESS_INTERVAL rows/lines can take a while to reach.
+fn log_progress(unit: &str, count: usize, started: std::time::Instant) {
+    let elapsed = started.elapsed().as_secs_f64();
+    let rate = count as f64 / elapsed.max(f64::EPSILON);
+    eprintln!("  ...read {count} CAN log {unit} ({elapsed:.1}s elapsed, {rate:.0} {unit}/s)");
+}
+
+// Shared plumbing for the plain-text, line-oriented formats (candump, ASC):
+// read the whole file as text, skip lines `is_header` recognises as not being
+// data frames, and parse the rest with `parse_line`.
+fn read_can_messages_lines(
+    log_path: &Path,
+    can_ts_offs: Nanos,
+    strict: bool,
+    is_header: impl Fn(&str) -> bool,
+    parse_line: impl Fn(&str, Nanos) -> Result<CANMessage, Box<dyn Error>>,
+) -> Result<(Vec<CANMessage>, Vec<ParseError>), Box<dyn Error>> {
+    let contents = std::fs::read_to_string(log_path)?;
+
+    let mut messages = vec![];
+    let mut errors = vec![];
+    let mut reorder = ReorderBuffer::new(REORDER_WINDOW);
+    let started = std::time::Instant::now();
+
+    for (row, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() || is_header(line) {
+            continue;
+        }
+
+        match parse_line(line, can_ts_offs) {
+            Ok(m) => messages.extend(reorder.push(m)),
+            Err(error) if strict => {
+                return Err(format!("row {row}: {error} (record: {line:?})").into())
+            }
+            Err(error) => errors.push(ParseError {
+                row,
+                record: line.to_string(),
+                error,
+            }),
+        }
+
+        if row > 0 && row % PROGRESS_INTERVAL == 0 {
+            log_progress("lines", row, started);
+        }
+    }
+    messages.extend(reorder.finish());
+
+    Ok((messages, errors))
+}
+
+// Vector ASC traces start with a few header/comment lines before the data frames.
+fn is_asc_header_line(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("date ")
+        || line.starts_with("base ")
+        || line.starts_with("internal events")
+        || line.starts_with("no internal events")
+        || line.starts_with("Begin Triggerblock")
+        || line.starts_with("End TriggerBlock")
+        || line.starts_with("//")
+}
+
+// Read all CAN messages from a CAN log, in whichever of the supported formats it's in.
+//
+// Unparseable rows/lines (bad hex, missing fields, non-numeric timestamp) are skipped
+// and accumulated into the returned `Vec<ParseError>` rather than aborting the whole
+// run, unless `strict` is set, in which case the first bad row is returned as an `Err`.
+pub fn read_can_messages(
+    csv_log_path: &Path,
+    can_ts_offs: Nanos,
+    strict: bool,
+    format: CanLogFormat,
+    interface_bus: &HashMap<String, u8>,
+) -> Result<(Vec<CANMessage>, Vec<ParseError>), Box<dyn Error>> {
+    eprintln!("Opening CAN log {:?}...", csv_log_path);
+
+    let (messages, errors) = match format {
+        CanLogFormat::Csv => read_can_messages_csv(csv_log_path, can_ts_offs, strict)?,
+        CanLogFormat::Candump => read_can_messages_lines(
+            csv_log_path,
+            can_ts_offs,
+            strict,
+            |_line| false,
+            |line, ts| CANMessage::parse_candump_line(line, ts, interface_bus),
+        )?,
+        CanLogFormat::VectorAsc => read_can_messages_lines(
+            csv_log_path,
+            can_ts_offs,
+            strict,
+            is_asc_header_line,
+            CANMessage::parse_asc_line,
+        )?,
+    };
+
+    let messages = messages
+        .into_iter()
         // TODO: For now dropping any CAN timestamp that comes before the video
         // started. Could conceivably adjust the start earlier instead and have empty video
         .filter(|m| m.timestamp >= 0)
-        // When the log contains >1 bus of data, the messages can be slightly out
-        // of order
-        .sorted()
-        .collect())
+        // Already merged into (near enough) timestamp order by each reader's ReorderBuffer
+        .collect();
+
+    Ok((messages, errors))
+}
+
+// Per-message allow/deny filter applied to CAN input before it's written to the route,
+// keyed on (bus, CAN ID). `allow` of `None` means "allow everything not explicitly
+// denied"; once any allow entry is given it means "only these, minus denies".
+#[derive(Default)]
+pub struct CanFilter {
+    allow: Option<HashSet<(Option<u8>, u32)>>,
+    deny: HashSet<(Option<u8>, u32)>,
+}
+
+impl CanFilter {
+    pub fn permits(&self, bus_no: u8, can_id: u32) -> bool {
+        if self.deny.contains(&(Some(bus_no), can_id)) || self.deny.contains(&(None, can_id)) {
+            return false;
+        }
+        match &self.allow {
+            None => true,
+            Some(allow) => {
+                allow.contains(&(Some(bus_no), can_id)) || allow.contains(&(None, can_id))
+            }
+        }
+    }
+}
+
+// Parse `--can-interface-bus` entries of the form "iface=bus", e.g. "vcan-front=0", for
+// candump logs whose interface names don't end in the bus number already.
+pub fn parse_interface_bus(specs: &[String]) -> Result<HashMap<String, u8>, Box<dyn Error>> {
+    specs
+        .iter()
+        .map(|spec| {
+            let (iface, bus) = spec
+                .split_once('=')
+                .ok_or_else(|| format!("--can-interface-bus {spec:?} is missing '='"))?;
+            Ok((iface.to_string(), bus.parse()?))
+        })
+        .collect()
+}
+
+// Parse `--can-filter` entries of the form `[!][bus:](hex_id|name)`, e.g. "0x7e0",
+// "1:0x7e0", "!0x7df", "ENGINE_DATA". A leading '!' denies that message; anything else
+// allows it. Names are resolved via `dbc`; a name entry is an error if no DBC was given.
+pub fn parse_can_filter(
+    specs: &[String],
+    dbc: Option<&MessageDb>,
+) -> Result<CanFilter, Box<dyn Error>> {
+    let mut allow = HashSet::new();
+    let mut deny = HashSet::new();
+    let mut has_allow = false;
+
+    for spec in specs {
+        let (is_deny, spec) = match spec.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, spec.as_str()),
+        };
+
+        let (bus, selector) = match spec.split_once(':') {
+            Some((bus, rest)) => (Some(bus.parse::<u8>()?), rest),
+            None => (None, spec),
+        };
+
+        let can_id = if let Some(hex) = selector.strip_prefix("0x") {
+            u32::from_str_radix(hex, 16)?
+        } else if let Ok(id) = selector.parse::<u32>() {
+            id
+        } else {
+            let dbc = dbc.ok_or_else(|| {
+                format!("--can-filter {spec:?} names a message, but no --dbc was given")
+            })?;
+            dbc.id_for_name(selector)
+                .ok_or_else(|| format!("Unknown message name {selector:?} in --can-filter"))?
+        };
+
+        if is_deny {
+            deny.insert((bus, can_id));
+        } else {
+            has_allow = true;
+            allow.insert((bus, can_id));
+        }
+    }
+
+    Ok(CanFilter {
+        allow: has_allow.then_some(allow),
+        deny,
+    })
 }
 
 #[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
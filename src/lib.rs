@@ -1,3 +1,5 @@
+pub mod dbc;
+pub mod export;
 pub mod input;
 pub mod qlog;
 pub mod video;
@@ -1,20 +1,25 @@
 use chrono::{DateTime, Local};
 use clap::Parser;
-use itertools::{merge, Itertools};
+use itertools::merge;
+use make_cabana_route::dbc::MessageDb;
 use make_cabana_route::input::{
-    expand_alerts, find_missing_can_messages, read_can_messages, CANMessage, LogInput,
+    expand_alerts, find_missing_can_messages, parse_can_filter, parse_interface_bus,
+    read_can_messages, trim_and_rebase, CANMessage, CanFilter, CanLogFormat, LogInput, ParseError,
 };
 use make_cabana_route::log_capnp::sentinel::SentinelType;
-use make_cabana_route::qlog::QlogWriter;
-use make_cabana_route::video::{SegmentVideoEncoder, SourceVideo};
+use make_cabana_route::qlog::{QlogCompression, QlogWriter};
+use make_cabana_route::video::{EncodeConfig, SegmentVideoEncoder, SourceVideo, Size};
 use make_cabana_route::Nanos;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::error::Error;
 use std::fs::{self, File, Permissions};
 use std::io::Write;
 use std::os::unix::prelude::PermissionsExt;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, SystemTime};
 
 // Duration of a route segment
 const SEGMENT_NANOS: Nanos = Duration::from_secs(60).as_nanos() as Nanos;
@@ -22,8 +27,9 @@ const SEGMENT_NANOS: Nanos = Duration::from_secs(60).as_nanos() as Nanos;
 // Each CAN event can span up to this long (effectively, giving all those messages the same timestamp)
 const CAN_EVENT_TIME: Nanos = Duration::from_millis(10).as_nanos() as Nanos;
 
-// Insert a thumbnail at these intervals
-const THUMBNAIL_INTERVAL: Nanos = Duration::from_millis(2500).as_nanos() as Nanos;
+// Thumbnails are placed at detected scene cuts, but a long static stretch still gets
+// one at least this often.
+const MAX_THUMBNAIL_INTERVAL: Nanos = Duration::from_secs(10).as_nanos() as Nanos;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -38,6 +44,139 @@ struct Args {
 
     /// Optional filter. If set, only process logs containing this string.
     filter_by: Option<String>,
+
+    /// Abort on the first unparseable CAN log row, instead of skipping it
+    /// and reporting a summary at the end.
+    #[arg(long)]
+    strict: bool,
+
+    /// Override the source video's transfer characteristic, for sources that
+    /// mislabel it (e.g. "smpte2084" for PQ HDR, "arib-std-b67" for HLG).
+    #[arg(long)]
+    color_transfer: Option<String>,
+
+    /// After processing, concatenate each route's segment videos into a single
+    /// fast-start MP4 at <data_dir>/<logfile_stem>.mp4, for sharing outside Cabana.
+    #[arg(long)]
+    export_mp4: bool,
+
+    /// Keep running, re-processing logs whose yaml_path, logfile or video have
+    /// changed since the last pass. Useful while tuning `sync` offsets.
+    #[arg(long)]
+    watch: bool,
+
+    /// Longest edge of the full-res output video, in pixels.
+    #[arg(long, default_value_t = 1280)]
+    video_width: u32,
+
+    /// Longest edge of the low-res qcamera preview rendition, in pixels.
+    #[arg(long, default_value_t = 526)]
+    qcamera_width: u32,
+
+    /// Longest edge of embedded JPEG thumbnails, in pixels.
+    #[arg(long, default_value_t = 640)]
+    thumbnail_width: u32,
+
+    /// x265 CRF for the full-res video. Lower is higher quality and bigger files.
+    #[arg(long, default_value_t = 28)]
+    crf: u32,
+
+    /// x265 CRF for the qcamera preview rendition.
+    #[arg(long, default_value_t = 32)]
+    qcamera_crf: u32,
+
+    /// x265 preset for both renditions (e.g. "ultrafast" .. "veryslow").
+    #[arg(long, default_value = "medium")]
+    preset: String,
+
+    /// Output frame rate; source frames are dropped to meet it.
+    #[arg(long, default_value_t = 20)]
+    fps: u32,
+
+    /// JPEG quality (0-100) for embedded thumbnails.
+    #[arg(long, default_value_t = 80)]
+    jpeg_quality: u8,
+
+    /// Compression format for each segment's qlog: "bz2" (openpilot/Cabana's
+    /// original format) or "zstd" (faster and usually smaller, needs a newer
+    /// Cabana/openpilot to read).
+    #[arg(long, default_value = "bz2")]
+    rlog_compression: String,
+
+    /// Compression level passed to --rlog-compression's backend: bz2 takes 1-9
+    /// (default 6), zstd takes its own wider range (default 0). Unset uses that
+    /// backend's default.
+    #[arg(long)]
+    rlog_compression_level: Option<u32>,
+
+    /// Trim the route to start this many seconds into the video (pts=0), dropping
+    /// everything before it and rebasing the rest so the trimmed route again starts at t=0.
+    #[arg(long)]
+    start: Option<f64>,
+
+    /// Trim the route to end this many seconds into the video (pts=0), dropping
+    /// everything at or after it.
+    #[arg(long)]
+    end: Option<f64>,
+
+    /// Path to a DBC file. Used to resolve message names in --can-filter, and passed
+    /// through to the generated Cabana launch script so it can decode signals at replay time.
+    #[arg(long)]
+    dbc: Option<PathBuf>,
+
+    /// Restrict which CAN messages get written to the route. Repeatable; each entry is
+    /// `[!][bus:](hex_id|name)`, e.g. "0x7e0", "1:0x7e0", "!0x7df", "ENGINE_DATA". A
+    /// leading '!' denies that message; anything else allows it, and switches to an
+    /// allow-list (so once any allow entry is given, only allowed messages pass).
+    #[arg(long)]
+    can_filter: Vec<String>,
+
+    /// Map a candump interface name to a bus number, e.g. "vcan-front=0". Repeatable.
+    /// Interfaces not listed fall back to the trailing digits of their name (so "can1"
+    /// is bus 1), or bus 0 if there are none.
+    #[arg(long)]
+    can_interface_bus: Vec<String>,
+}
+
+impl Args {
+    fn encode_config(&self) -> EncodeConfig {
+        EncodeConfig {
+            video_size: Size::Scale(self.video_width),
+            qcamera_size: Size::Scale(self.qcamera_width),
+            thumbnail_size: Size::Scale(self.thumbnail_width),
+            crf: self.crf,
+            qcamera_crf: self.qcamera_crf,
+            preset: self.preset.clone(),
+            target_fps: self.fps,
+            jpeg_quality: self.jpeg_quality,
+        }
+    }
+}
+
+// How often to poll yaml_path and every referenced input for changes in --watch mode.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+fn parse_color_transfer(
+    name: &str,
+) -> Result<ffmpeg::color::TransferCharacteristic, Box<dyn Error>> {
+    use ffmpeg::color::TransferCharacteristic as Xfer;
+    match name {
+        "bt709" => Ok(Xfer::BT709),
+        "smpte2084" | "pq" => Ok(Xfer::SMPTE2084),
+        "arib-std-b67" | "hlg" => Ok(Xfer::ARIB_STD_B67),
+        "bt2020-10" => Ok(Xfer::BT2020_10),
+        "bt2020-12" => Ok(Xfer::BT2020_12),
+        "linear" => Ok(Xfer::Linear),
+        other => Err(format!("Unrecognised --color-transfer value {other:?}").into()),
+    }
+}
+
+fn parse_rlog_compression(name: &str) -> Result<QlogCompression, Box<dyn Error>> {
+    match name {
+        "bz2" => Ok(QlogCompression::Bz2),
+        "zstd" | "zst" => Ok(QlogCompression::Zstd),
+        other => Err(format!("Unrecognised --rlog-compression value {other:?}").into()),
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -46,11 +185,30 @@ struct LogInfo {
     fingerprint: String,
     route_timestamp: Option<DateTime<Local>>,
     logfile: PathBuf,
+    // Defaults to the CSV format this tool has always produced, so existing
+    // routes.yml files don't need updating to keep working.
+    #[serde(default)]
+    format: CanLogFormat,
+    // A path to the dashcam video, or "-" to read it from stdin instead (e.g. a
+    // dashcam stream piped in live), via SourceVideo::from_reader().
     video: Option<PathBuf>,
     sync: Option<LogSyncInfo>,
+    // Cache for route_timestamp() below, resolved the first time it's called. Needed
+    // because segment encoding happens concurrently across a worker pool, and the mp4
+    // export and launch script writer call it again afterwards; without caching, a
+    // stdin video's Local::now() fallback would give each call site a different answer.
+    #[serde(skip)]
+    resolved_timestamp: std::sync::OnceLock<DateTime<Local>>,
 }
 
+// The "-" convention a `video:` field uses to mean "read from stdin" rather than a file.
+const STDIN_VIDEO: &str = "-";
+
 impl LogInfo {
+    fn video_is_stdin(&self) -> bool {
+        self.video.as_deref().map(Path::as_os_str) == Some(std::ffi::OsStr::new(STDIN_VIDEO))
+    }
+
     // Convert relative paths to absolute ones, return an error if paths don't exist
     fn canonicalise_paths(&mut self, relative_to: &Path) -> Result<(), Box<dyn Error>> {
         let relative_to = relative_to
@@ -63,6 +221,10 @@ impl LogInfo {
         // Check logfile exists
         self.logfile.metadata()?;
 
+        if self.video_is_stdin() {
+            return Ok(());
+        }
+
         if let Some(video) = &self.video {
             let video = relative_to.join(video);
             // Check video exists
@@ -78,23 +240,28 @@ impl LogInfo {
     // If route_timestamp is set in the YAML file, use this. Otherwise,
     // use the modification date of the video file or the log file..
     fn route_timestamp(&self) -> DateTime<Local> {
-        if let Some(ts) = self.route_timestamp {
-            ts
-        } else if let Some(video) = &self.video {
-            video
-                .metadata()
-                .expect("video file should already exist")
-                .modified()
-                .unwrap()
-                .into()
-        } else {
-            self.logfile
-                .metadata()
-                .expect("logfile checked already")
-                .modified()
-                .expect("logfile checked already")
-                .into()
-        }
+        *self.resolved_timestamp.get_or_init(|| {
+            if let Some(ts) = self.route_timestamp {
+                ts
+            } else if self.video_is_stdin() {
+                // A stdin stream has no file mtime to fall back on.
+                Local::now()
+            } else if let Some(video) = &self.video {
+                video
+                    .metadata()
+                    .expect("video file should already exist")
+                    .modified()
+                    .unwrap()
+                    .into()
+            } else {
+                self.logfile
+                    .metadata()
+                    .expect("logfile checked already")
+                    .modified()
+                    .expect("logfile checked already")
+                    .into()
+            }
+        })
     }
 
     // Segment directories in the data directory are based on the route timestamp,
@@ -163,28 +330,128 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     let args = Args::parse();
 
-    let f = std::fs::File::open(&args.yaml_path)?;
-    let mut logs: Vec<LogInfo> = serde_yaml::from_reader(f)?;
+    let color_transfer_override = args
+        .color_transfer
+        .as_deref()
+        .map(parse_color_transfer)
+        .transpose()?;
+    let encode_config = args.encode_config();
+    let rlog_compression = parse_rlog_compression(&args.rlog_compression)?;
+    let start_ns: Nanos = args.start.map_or(0, |s| (s * 1_000_000_000.0) as Nanos);
+    let end_ns: Option<Nanos> = args.end.map(|s| (s * 1_000_000_000.0) as Nanos);
+    let dbc = args.dbc.as_deref().map(MessageDb::load).transpose()?;
+    let can_filter = parse_can_filter(&args.can_filter, dbc.as_ref())?;
+    let interface_bus = parse_interface_bus(&args.can_interface_bus)?;
+
+    // Tracks the input mtime each LogInfo was last processed with, keyed by logfile
+    // path (which is unique per entry). Used in --watch mode to skip logs whose
+    // inputs haven't changed, the same way an unchanged qcamera.ts is skipped within
+    // a route.
+    let mut last_processed: HashMap<PathBuf, SystemTime> = HashMap::new();
+
+    loop {
+        let f = std::fs::File::open(&args.yaml_path)?;
+        let mut logs: Vec<LogInfo> = serde_yaml::from_reader(f)?;
+
+        // Fix up paths, this will also error out early if any files are not found
+        for info in &mut logs {
+            info.canonicalise_paths(&args.yaml_path)?;
+        }
 
-    // Fix up paths, this will also error out early if any files are not found
-    for info in &mut logs {
-        info.canonicalise_paths(&args.yaml_path)?;
-    }
+        // Start this pass's warnings.txt fresh, so re-running the same route in
+        // --watch mode doesn't pile up duplicate warnings from earlier passes.
+        let warnings_path = args.data_dir.join("warnings.txt");
+        if warnings_path.exists() {
+            fs::remove_file(&warnings_path)?;
+        }
 
-    for info in &logs {
-        if let Some(ref filter_by) = args.filter_by {
-            if !info.log_matches(filter_by) {
+        for info in &logs {
+            if let Some(ref filter_by) = args.filter_by {
+                if !info.log_matches(filter_by) {
+                    continue;
+                }
+            }
+
+            let mtime = input_mtime(info)?;
+            if last_processed.get(&info.logfile) == Some(&mtime) {
                 continue;
             }
+
+            process_log(
+                info,
+                &args.data_dir,
+                args.strict,
+                color_transfer_override,
+                args.export_mp4,
+                &encode_config,
+                rlog_compression,
+                args.rlog_compression_level,
+                start_ns,
+                end_ns,
+                &can_filter,
+                args.dbc.as_deref(),
+                dbc.as_ref(),
+                &interface_bus,
+            )?;
+            last_processed.insert(info.logfile.clone(), mtime);
         }
 
-        process_log(info, &args.data_dir)?;
+        if !args.watch {
+            return Ok(());
+        }
+
+        eprintln!("Watching {:?} for changes (Ctrl+C to stop)...", args.yaml_path);
+        let yaml_mtime = args.yaml_path.metadata()?.modified()?;
+        loop {
+            std::thread::sleep(WATCH_POLL_INTERVAL);
+
+            if args.yaml_path.metadata()?.modified()? != yaml_mtime {
+                // routes.yml itself changed (e.g. new sync offsets), so re-parse and
+                // re-process every log, not only those whose logfile/video changed
+                last_processed.clear();
+                break;
+            }
+
+            let any_input_changed = logs.iter().any(|info| {
+                input_mtime(info)
+                    .map(|mtime| last_processed.get(&info.logfile) != Some(&mtime))
+                    .unwrap_or(false)
+            });
+            if any_input_changed {
+                break;
+            }
+        }
     }
+}
 
-    Ok(())
+// The most recent modification time across a LogInfo's logfile and (if present) video,
+// used to detect whether it needs reprocessing in --watch mode.
+fn input_mtime(info: &LogInfo) -> Result<SystemTime, Box<dyn Error>> {
+    let mut mtime = info.logfile.metadata()?.modified()?;
+    if !info.video_is_stdin() {
+        if let Some(video) = &info.video {
+            mtime = mtime.max(video.metadata()?.modified()?);
+        }
+    }
+    Ok(mtime)
 }
 
-fn process_log(info: &LogInfo, data_dir: &Path) -> Result<(), Box<dyn Error>> {
+fn process_log(
+    info: &LogInfo,
+    data_dir: &Path,
+    strict: bool,
+    color_transfer_override: Option<ffmpeg::color::TransferCharacteristic>,
+    export_mp4: bool,
+    encode_config: &EncodeConfig,
+    rlog_compression: QlogCompression,
+    rlog_compression_level: Option<u32>,
+    start_ns: Nanos,
+    end_ns: Option<Nanos>,
+    can_filter: &CanFilter,
+    dbc_path: Option<&Path>,
+    dbc: Option<&MessageDb>,
+    interface_bus: &HashMap<String, u8>,
+) -> Result<(), Box<dyn Error>> {
     if info.video.is_some() && info.sync.is_none() {
         panic!("Video {0:?} requires a sync section to match", info.video); // TODO: better error handling!
     }
@@ -194,18 +461,40 @@ fn process_log(info: &LogInfo, data_dir: &Path) -> Result<(), Box<dyn Error>> {
     // Read CAN messages, and sort them by timestamp
     // (not guaranteed from the CSV log, if there are CAN messages from >1 bus)
     eprintln!("Loading CAN messages {0:?}...", info.logfile);
-    let can_inputs = read_can_messages(&info.logfile, can_ts_offs)?;
+    let (can_inputs, parse_errors) =
+        read_can_messages(&info.logfile, can_ts_offs, strict, info.format, interface_bus)?;
+
+    if !parse_errors.is_empty() {
+        eprintln!(
+            "Skipped {} unparseable row(s) in {:?}, see warnings.txt",
+            parse_errors.len(),
+            info.logfile
+        );
+        write_warnings(data_dir, &info.logfile, &parse_errors)?;
+    }
 
+    // Look for gaps in the unfiltered stream, so a --can-filter that only keeps a few
+    // IDs doesn't spuriously flag every other ID's silence as lost messages.
     let alerts_vec = find_missing_can_messages(&can_inputs);
     let alerts = expand_alerts(alerts_vec).into_iter();
 
+    let can_inputs: Vec<CANMessage> = can_inputs
+        .into_iter()
+        .filter(|m| can_filter.permits(m.bus_no, m.can_id))
+        .collect();
+
     let mut source_video = None;
     let mut video_properties = None;
 
     if let Some(video_path) = &info.video {
-        eprintln!("Opening video {video_path:?}...");
-        let sv = SourceVideo::new(video_path)?;
-        video_properties = Some(sv.properties());
+        let sv = if info.video_is_stdin() {
+            eprintln!("Opening video from stdin...");
+            SourceVideo::from_reader(std::io::stdin(), encode_config)?
+        } else {
+            eprintln!("Opening video {video_path:?}...");
+            SourceVideo::new(video_path)?
+        };
+        video_properties = Some(sv.properties(encode_config, color_transfer_override)?);
         source_video = Some(sv);
     };
 
@@ -215,13 +504,15 @@ fn process_log(info: &LogInfo, data_dir: &Path) -> Result<(), Box<dyn Error>> {
         Some(source_video) => {
             // If we have video and CAN message inputs, merge them together
             // keeping the output sorted by timestamp
-            let frames = source_video.video_frames().map(LogInput::Frame);
+            let frames = source_video.video_frames(encode_config).map(LogInput::Frame);
             Box::new(merge(merge(can_inputs, frames), alerts))
         }
         // If only have CAN messages, can iterate them as-is
         None => Box::new(merge(can_inputs, alerts)),
     };
 
+    let inputs = trim_and_rebase(inputs, start_ns, end_ns);
+
     let mut inputs = inputs.peekable();
 
     if inputs.peek().map(|i| i.timestamp()).unwrap_or(0) > SEGMENT_NANOS {
@@ -229,108 +520,303 @@ fn process_log(info: &LogInfo, data_dir: &Path) -> Result<(), Box<dyn Error>> {
         // TODO: better error handling
     }
 
-    // Sort the inputs and group them into segments
-    let segments = inputs.group_by(|input| input.timestamp() / SEGMENT_NANOS);
-
-    for (segment_idx, inputs) in &segments {
-        let mut inputs = inputs.peekable();
-
-        let mut frame_id = 0;
+    // Hand segments to the worker pool as soon as the sorted input stream moves past
+    // their time window, instead of bucketing the whole route into a BTreeMap up front:
+    // that kept every input for the whole route in memory at once regardless of how
+    // the CAN log itself was read, which defeated the point of streaming it in. The
+    // channel is bounded so a fast producer can't run arbitrarily far ahead of the
+    // worker pool either.
+    let num_workers = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+
+    let (segment_tx, segment_rx) = mpsc::sync_channel::<(i64, bool, Vec<LogInput>)>(num_workers * 2);
+    let segment_rx = Mutex::new(segment_rx);
+    let completed = AtomicUsize::new(0);
+    let produced = AtomicUsize::new(0);
+    let mut segment_indices: Vec<i64> = vec![];
+
+    std::thread::scope(|scope| {
+        for _ in 0..num_workers {
+            scope.spawn(|| loop {
+                let job = segment_rx.lock().unwrap().recv();
+                let Ok((segment_idx, is_last_segment, inputs)) = job else {
+                    break;
+                };
+
+                let result = encode_segment(
+                    info,
+                    data_dir,
+                    segment_idx,
+                    is_last_segment,
+                    inputs,
+                    &video_properties,
+                    encode_config,
+                    rlog_compression,
+                    rlog_compression_level,
+                    dbc,
+                );
+                let done = completed.fetch_add(1, Ordering::SeqCst) + 1;
+                let so_far = produced.load(Ordering::SeqCst);
+
+                match result {
+                    Ok(frame_count) => eprintln!(
+                        "Progress: {done}/{so_far} segments complete \
+                         (segment {segment_idx}: {frame_count} frames encoded)"
+                    ),
+                    Err(e) => eprintln!("Error encoding segment {segment_idx}: {e}"),
+                }
+            });
+        }
 
-        let segment_dir = info.segment_dir_path(data_dir, segment_idx);
+        // The merged stream is already sorted by timestamp, so a segment is known to be
+        // complete (and can be handed off) as soon as an input for the next one arrives.
+        // Only the very last segment needs a one-item lookahead to know it's the last.
+        let mut current: Option<(i64, Vec<LogInput>)> = None;
+        for input in inputs {
+            let idx = input.timestamp() / SEGMENT_NANOS;
+            match &mut current {
+                Some((cur_idx, items)) if *cur_idx == idx => items.push(input),
+                _ => {
+                    if let Some((prev_idx, items)) = current.take() {
+                        segment_indices.push(prev_idx);
+                        produced.fetch_add(1, Ordering::SeqCst);
+                        segment_tx.send((prev_idx, false, items)).unwrap();
+                    }
+                    current = Some((idx, vec![input]));
+                }
+            }
+        }
+        if let Some((idx, items)) = current {
+            segment_indices.push(idx);
+            produced.fetch_add(1, Ordering::SeqCst);
+            segment_tx.send((idx, true, items)).unwrap();
+        }
+        drop(segment_tx); // Closes the channel so workers exit once the queue drains
+    });
+
+    if export_mp4 && video_properties.is_some() {
+        let segment_videos: Vec<PathBuf> = segment_indices
+            .iter()
+            .map(|idx| info.segment_dir_path(data_dir, *idx).join("fcamera.ts"))
+            .filter(|p| p.exists())
+            .collect();
+        let mp4_name = format!("{}.mp4", info.logfile.file_stem().unwrap().to_str().unwrap());
+        make_cabana_route::export::concat_segments_to_mp4(&segment_videos, &data_dir.join(mp4_name))?;
+    }
 
-        eprintln!("Writing segment {segment_idx} to {segment_dir:?}...");
+    write_launch_script(info, data_dir, dbc_path)?;
 
-        std::fs::create_dir_all(&segment_dir)?;
+    Ok(())
+}
 
-        let mut qlog = QlogWriter::new(segment_dir.join("qlog.bz2"))?;
-        let seg_video_path = segment_dir.join("qcamera.ts");
+// Encode a single segment: its fcamera.ts (full-res) and qcamera.ts (low-res preview)
+// videos (if any) and its qlog. Segments are fully independent (own directory, own
+// qlog, own video files), so this is safe to call concurrently for different
+// segment_idx values from a worker pool.
+fn encode_segment(
+    info: &LogInfo,
+    data_dir: &Path,
+    segment_idx: i64,
+    is_last_segment: bool,
+    inputs: Vec<LogInput>,
+    video_properties: &Option<make_cabana_route::video::VideoProperties>,
+    encode_config: &EncodeConfig,
+    rlog_compression: QlogCompression,
+    rlog_compression_level: Option<u32>,
+    dbc: Option<&MessageDb>,
+) -> Result<u32, Box<dyn Error>> {
+    let mut inputs = inputs.into_iter().peekable();
+
+    let mut frame_id = 0;
+
+    let segment_dir = info.segment_dir_path(data_dir, segment_idx);
+
+    eprintln!("Writing segment {segment_idx} to {segment_dir:?}...");
+
+    std::fs::create_dir_all(&segment_dir)?;
+
+    let qlog_path = segment_dir.join(format!("qlog.{}", rlog_compression.extension()));
+    let mut qlog = QlogWriter::new(qlog_path, rlog_compression, rlog_compression_level)?;
+    let fcamera_path = segment_dir.join("fcamera.ts");
+    let qcamera_path = segment_dir.join("qcamera.ts");
+
+    // Sidecar of decoded signal values, alongside the raw qlog, when a --dbc was given.
+    // qlog itself has no field for decoded signals (openpilot logs raw CAN and leaves
+    // decoding to replay time), so this is the one place in the route that surfaces
+    // what MessageDb::decode() actually produces, for verifying a DBC against the log.
+    let mut signals_writer = match dbc {
+        Some(_) => {
+            let mut writer = csv::Writer::from_path(segment_dir.join("signals.csv"))?;
+            writer.write_record(["timestamp_ns", "can_id", "signal", "value"])?;
+            Some(writer)
+        }
+        None => None,
+    };
 
-        let mut segment_video = if let Some(properties) = &video_properties {
-            if !seg_video_path.try_exists()? {
-                Some(SegmentVideoEncoder::new(&seg_video_path, properties)?)
-            } else {
-                // Don't encode new a segment video if the it already exists, as this is the slowest
-                // and most CPU intensive part
-                eprintln!("Skipping existing {seg_video_path:?}");
-                None
-            }
+    let mut fcamera_video = if let Some(properties) = video_properties {
+        if !fcamera_path.try_exists()? {
+            Some(SegmentVideoEncoder::new(
+                &fcamera_path,
+                properties,
+                false,
+                false,
+                encode_config,
+            )?)
         } else {
+            // Don't encode new segment videos if they already exist, as this is the
+            // slowest and most CPU intensive part
+            eprintln!("Skipping existing {fcamera_path:?}");
             None
-        };
-
-        let first_ts = inputs.peek().map(|f| f.timestamp()).unwrap_or(0);
-
-        qlog.write_init_data(first_ts);
+        }
+    } else {
+        None
+    };
 
-        if segment_idx == 0 {
-            qlog.write_car_params(first_ts, &info.car, &info.fingerprint);
-            qlog.write_sentinel(first_ts, SentinelType::StartOfRoute);
+    let mut qcamera_video = if let Some(properties) = video_properties {
+        if !qcamera_path.try_exists()? {
+            Some(SegmentVideoEncoder::new(
+                &qcamera_path,
+                &properties.qcamera_variant(),
+                false,
+                true,
+                encode_config,
+            )?)
+        } else {
+            eprintln!("Skipping existing {qcamera_path:?}");
+            None
         }
-        qlog.write_sentinel(first_ts, SentinelType::StartOfSegment);
+    } else {
+        None
+    };
 
-        let mut last_thumbnail: Nanos = 0;
+    let first_ts = inputs.peek().map(|f| f.timestamp()).unwrap_or(0);
 
-        let mut can_msgs: Vec<CANMessage> = vec![];
+    qlog.write_init_data(first_ts);
 
-        for input in inputs {
-            // Flush the current set of CAN messages to an event
-            // in qlog whenever CAN_EVENT_LEN time has passed
-            if !can_msgs.is_empty() && input.timestamp() - can_msgs[0].timestamp() > CAN_EVENT_TIME
-            {
-                qlog.write_can(&can_msgs);
-                can_msgs.clear();
-            }
+    if segment_idx == 0 {
+        qlog.write_car_params(first_ts, &info.car, &info.fingerprint);
+        qlog.write_sentinel(first_ts, SentinelType::StartOfRoute);
+    }
+    qlog.write_sentinel(first_ts, SentinelType::StartOfSegment);
 
-            match input {
-                LogInput::CAN(can_msg) => {
-                    can_msgs.push(can_msg);
-                }
-                LogInput::Frame(ref frame) => {
-                    let ts = input.timestamp();
+    let mut last_thumbnail: Nanos = 0;
 
-                    if let Some(ref mut encode) = segment_video {
-                        encode.send_frame(frame)?;
-                    }
+    let mut can_msgs: Vec<CANMessage> = vec![];
+
+    for input in inputs {
+        // Flush the current set of CAN messages to an event
+        // in qlog whenever CAN_EVENT_TIME has passed
+        if !can_msgs.is_empty() && input.timestamp() - can_msgs[0].timestamp() > CAN_EVENT_TIME {
+            qlog.write_can(&can_msgs);
+            can_msgs.clear();
+        }
 
-                    qlog.write_frame_encode_idx(ts, segment_idx as i32, frame_id);
-                    if ts - last_thumbnail > THUMBNAIL_INTERVAL {
-                        let jpeg = frame.encode_jpeg()?;
-                        qlog.write_thumbnail(ts, ts + THUMBNAIL_INTERVAL, frame_id, &jpeg);
-                        last_thumbnail = ts;
+        match input {
+            LogInput::CAN(can_msg) => {
+                if let (Some(dbc), Some(writer)) = (dbc, signals_writer.as_mut()) {
+                    for (name, value) in dbc.decode(can_msg.can_id, &can_msg.data) {
+                        writer.write_record([
+                            can_msg.timestamp().to_string(),
+                            can_msg.can_id.to_string(),
+                            name,
+                            value.to_string(),
+                        ])?;
                     }
+                }
+                can_msgs.push(can_msg);
+            }
+            LogInput::Frame(ref frame) => {
+                let ts = input.timestamp();
 
-                    frame_id += 1;
+                if let Some(ref mut encode) = fcamera_video {
+                    encode.send_frame(&frame.frame)?;
                 }
-                LogInput::Alert(ref alert) => {
-                    qlog.write_alert(alert);
+                if let Some(ref mut encode) = qcamera_video {
+                    encode.send_frame(&frame.qcamera_frame)?;
                 }
+
+                qlog.write_frame_encode_idx(
+                    ts,
+                    segment_idx as i32,
+                    frame_id,
+                    make_cabana_route::log_capnp::encode_index::Type::FullHEVC,
+                );
+
+                if frame.is_scene_cut || ts - last_thumbnail > MAX_THUMBNAIL_INTERVAL {
+                    let jpeg = frame.encode_jpeg(encode_config);
+                    qlog.write_thumbnail(ts, ts + MAX_THUMBNAIL_INTERVAL, frame_id, &jpeg);
+                    last_thumbnail = ts;
+                }
+
+                frame_id += 1;
+            }
+            LogInput::Alert(ref alert) => {
+                qlog.write_alert(alert);
             }
         }
+    }
 
-        // Flush any final batch of CAN messages
-        qlog.write_can(&can_msgs);
+    // Flush any final batch of CAN messages
+    qlog.write_can(&can_msgs);
 
-        if let Some(encode) = segment_video {
-            encode.finish();
+    if let Some(mut writer) = signals_writer {
+        writer.flush()?;
+    }
 
-            if frame_id == 0 {
-                // No frames actually got written for this segment, so get rid of the
-                // zero byte video file (otherwise Openpilot complains)
-                println!("Warning: empty video segment. CAN log probably runs longer than video");
-                std::fs::remove_file(seg_video_path)?;
-            }
+    if let Some(encode) = fcamera_video {
+        encode.finish()?;
+
+        if frame_id == 0 {
+            // No frames actually got written for this segment, so get rid of the
+            // zero byte video file (otherwise Openpilot complains)
+            println!("Warning: empty video segment. CAN log probably runs longer than video");
+            std::fs::remove_file(fcamera_path)?;
         }
+    }
 
-        qlog.write_sentinel(0, SentinelType::EndOfSegment);
+    if let Some(encode) = qcamera_video {
+        encode.finish()?;
+
+        if frame_id == 0 {
+            std::fs::remove_file(qcamera_path)?;
+        }
     }
 
-    write_launch_script(info, data_dir)?;
+    qlog.write_sentinel(0, SentinelType::EndOfSegment);
+    if is_last_segment {
+        qlog.write_sentinel(0, SentinelType::EndOfRoute);
+    }
+
+    Ok(frame_id)
+}
+
+// Append a summary of unparseable CAN log rows/lines to warnings.txt in the data dir, so a
+// salvaged route still records what was dropped.
+fn write_warnings(
+    data_dir: &Path,
+    logfile: &Path,
+    parse_errors: &[ParseError],
+) -> Result<(), Box<dyn Error>> {
+    std::fs::create_dir_all(data_dir)?;
+    let mut warnings = File::options()
+        .create(true)
+        .append(true)
+        .open(data_dir.join("warnings.txt"))?;
+
+    writeln!(warnings, "{:?}:", logfile)?;
+    for error in parse_errors {
+        writeln!(warnings, "  {error}")?;
+    }
 
     Ok(())
 }
 
-fn write_launch_script(info: &LogInfo, data_dir: &Path) -> Result<(), Box<dyn Error>> {
+fn write_launch_script(
+    info: &LogInfo,
+    data_dir: &Path,
+    dbc_path: Option<&Path>,
+) -> Result<(), Box<dyn Error>> {
     /* Cabana doesn't have much of a feature for browsing local routes, so much a bunch of
     launcher scripts based on the CSV log file name.
 
@@ -343,13 +829,20 @@ fn write_launch_script(info: &LogInfo, data_dir: &Path) -> Result<(), Box<dyn Er
         Some(_) => "",
         _ => "--no-vipc",
     }; // If there's no video, Cabana won't open the route without this argument
+    // If a --dbc was given for --can-filter, also hand it to Cabana so it decodes the
+    // same messages' signals at replay time instead of just showing raw frames.
+    let dbc_arg = match dbc_path {
+        Some(path) => format!("--dbc {:?}", path),
+        None => String::new(),
+    };
     {
         let mut script = File::create(&script_path)?;
         script.write_all(b"#!/bin/sh\n")?;
         script.write_all(
             format!(
-                "cabana {} --data_dir \"$(dirname $0)\" $@ {}\n",
+                "cabana {} {} --data_dir \"$(dirname $0)\" $@ {}\n",
                 vipc_arg,
+                dbc_arg,
                 first_segment_dir.file_name().unwrap().to_str().unwrap(),
             )
             .as_bytes(),
@@ -5,17 +5,56 @@ use crate::Nanos;
 use bzip2::write::BzEncoder;
 use bzip2::Compression;
 use std::fs::File;
+use std::io::Write;
 use std::path::PathBuf;
 
-// Struct to wrap writing an qlog.bz2 file
+// Which compression format a route's qlog is written in. bz2 is the original
+// format openpilot/Cabana has always produced; zstd compresses faster and
+// usually smaller, at the cost of needing a newer Cabana/openpilot to read it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QlogCompression {
+    Bz2,
+    Zstd,
+}
+
+impl QlogCompression {
+    // File extension conventionally used for this compression format, e.g. "qlog.bz2".
+    pub fn extension(&self) -> &'static str {
+        match self {
+            QlogCompression::Bz2 => "bz2",
+            QlogCompression::Zstd => "zst",
+        }
+    }
+}
+
+// Struct to wrap writing a qlog file, in whichever compression format was requested.
 pub struct QlogWriter {
     last_timestamp: Nanos,
-    writer: BzEncoder<File>,
+    writer: Box<dyn Write>,
 }
 
 impl QlogWriter {
-    pub fn new(path: PathBuf) -> Result<Self, std::io::Error> {
-        let writer = BzEncoder::new(File::create(path)?, Compression::new(6));
+    // `level` is the compression backend's own level knob: bz2 takes 1-9 (defaulting to
+    // 6 if not given, clamped into range otherwise), zstd takes its own wider range
+    // (defaulting to 0, its "pick a sane default" level).
+    pub fn new(
+        path: PathBuf,
+        compression: QlogCompression,
+        level: Option<u32>,
+    ) -> Result<Self, std::io::Error> {
+        let file = File::create(path)?;
+        let writer: Box<dyn Write> = match compression {
+            QlogCompression::Bz2 => {
+                let level = level.unwrap_or(6).clamp(1, 9);
+                Box::new(BzEncoder::new(file, Compression::new(level)))
+            }
+            // auto_finish() writes the zstd frame trailer on drop, the same way
+            // BzEncoder flushes its trailer on drop above.
+            QlogCompression::Zstd => {
+                let level = level.map_or(0, |l| l as i32);
+                Box::new(zstd::Encoder::new(file, level)?.auto_finish())
+            }
+        };
         Ok(Self {
             writer,
             last_timestamp: 0,
@@ -74,11 +113,17 @@ impl QlogWriter {
         });
     }
 
-    pub fn write_frame_encode_idx(&mut self, mono_time: Nanos, segment_num: i32, frame_id: u32) {
+    pub fn write_frame_encode_idx(
+        &mut self,
+        mono_time: Nanos,
+        segment_num: i32,
+        frame_id: u32,
+        encode_type: log_capnp::encode_index::Type,
+    ) {
         self.write_event(mono_time, |event| {
             let mut encode_idx = event.init_road_encode_idx();
             encode_idx.set_frame_id(frame_id);
-            encode_idx.set_type(log_capnp::encode_index::Type::FullHEVC);
+            encode_idx.set_type(encode_type);
             encode_idx.set_encode_id(frame_id); // Seems this can be same as Frame ID?
             encode_idx.set_segment_num(segment_num);
             encode_idx.set_segment_id(frame_id); // Appears to be the same(!)
@@ -8,19 +8,68 @@ use ffmpeg::{
     codec, decoder, encoder, format, frame, media, software::scaling, Dictionary, Packet, Rational,
 };
 use jpeg_encoder;
+use std::ffi::c_void;
+use std::io::Read;
+use std::os::raw::c_int;
 use std::path::{Path, PathBuf};
+use std::ptr;
+
+// How an output's dimensions are derived from the source video's.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Size {
+    /// Scale so the longer edge is at most this many pixels, preserving aspect ratio.
+    /// If the source is already smaller, it's left as-is.
+    Scale(u32),
+    /// Use these exact dimensions, distorting the aspect ratio if necessary.
+    Exact(u32, u32),
+}
 
-const TARGET_FPS: u32 = 20;
-
-const TARGET_FRAME_NS: i64 = 1_000_000_000i64 / TARGET_FPS as i64;
+impl Size {
+    fn resolve(self, in_width: u32, in_height: u32) -> (u32, u32) {
+        match self {
+            Size::Exact(width, height) => (width, height),
+            Size::Scale(longest_edge) => {
+                if in_width >= in_height {
+                    let out_width = longest_edge.min(in_width);
+                    (out_width, out_width * in_height / in_width)
+                } else {
+                    let out_height = longest_edge.min(in_height);
+                    (out_height * in_width / in_height, out_height)
+                }
+            }
+        }
+    }
+}
 
-const JPEG_QUALITY: u8 = 80;
+// Runtime-tunable output geometry and encoder quality, threaded through
+// `SourceVideo::properties`, `SegmentVideoEncoder::new` and `SourceFrame::encode_jpeg`
+// so a caller isn't stuck with one hardcoded trade-off between fidelity and size.
+#[derive(Clone, Debug)]
+pub struct EncodeConfig {
+    pub video_size: Size,
+    pub qcamera_size: Size,
+    pub thumbnail_size: Size,
+    pub crf: u32,
+    pub qcamera_crf: u32,
+    pub preset: String,
+    pub target_fps: u32,
+    pub jpeg_quality: u8,
+}
 
-// TODO: consider making these runtime configurable
-const JPEG_MAX_WIDTH: u32 = 640;
-/// Maximum width of an embedded JPEG thumbnail
-const VIDEO_MAX_WIDTH: u32 = 1280;
-/// Maximum width of the output video frame
+impl Default for EncodeConfig {
+    fn default() -> Self {
+        Self {
+            video_size: Size::Scale(1280),
+            qcamera_size: Size::Scale(526),
+            thumbnail_size: Size::Scale(640),
+            crf: 28, // default is 28. lower == higher quality, bigger files.
+            qcamera_crf: 32, // qcamera is a low-bitrate preview, so a coarser crf is fine
+            preset: "medium".to_string(),
+            target_fps: 20,
+            jpeg_quality: 80,
+        }
+    }
+}
 
 struct FilterGraph {
     graph: ffmpeg::filter::graph::Graph,
@@ -35,7 +84,15 @@ pub struct SegmentVideoEncoder {
 }
 
 impl SegmentVideoEncoder {
-    pub fn new(path: &Path, properties: &VideoProperties, dump_info: bool) -> Result<Self> {
+    // `low_res` selects the config's qcamera CRF, suited to the small preview
+    // rendition, instead of the full-res archival stream's CRF.
+    pub fn new(
+        path: &Path,
+        properties: &VideoProperties,
+        dump_info: bool,
+        low_res: bool,
+        config: &EncodeConfig,
+    ) -> Result<Self> {
         let mut octx = format::output(path)
             .with_context(|| format!("Failed to create output context for {:?}", path))?;
 
@@ -51,9 +108,11 @@ impl SegmentVideoEncoder {
         video.set_width(properties.out_width);
         video.set_height(properties.out_height);
         video.set_format(properties.format);
-        video.set_frame_rate(Some(Rational::new(TARGET_FPS as i32, 1)));
+        video.set_frame_rate(Some(Rational::new(config.target_fps as i32, 1)));
         video.set_colorspace(properties.color_space);
         video.set_color_range(properties.color_range);
+        video.set_color_primaries(properties.color_primaries);
+        video.set_color_transfer_characteristic(properties.color_transfer);
 
         // This time base seems to be required by HEVC, but unsure how it's supposed
         // to be set
@@ -66,9 +125,10 @@ impl SegmentVideoEncoder {
 
         eprintln!("Writing segment video to {}...", path.display());
 
+        let crf = if low_res { config.qcamera_crf } else { config.crf };
         let mut x265_opts = Dictionary::new();
-        x265_opts.set("preset", "medium"); // default is medium. TODO: make configurable?
-        x265_opts.set("crf", "28"); // default is 28. lower == higher quality, bigger files.
+        x265_opts.set("preset", &config.preset);
+        x265_opts.set("crf", &crf.to_string());
         let encoder = video
             .open_with(x265_opts)
             .expect("error opening HEVC encoder");
@@ -88,9 +148,9 @@ impl SegmentVideoEncoder {
         })
     }
 
-    pub fn send_frame(&mut self, frame: &SourceFrame) -> Result<()> {
+    pub fn send_frame(&mut self, frame: &frame::Video) -> Result<()> {
         self.encoder
-            .send_frame(&frame.frame)
+            .send_frame(frame)
             .context("Failed to send frame to encoder")?;
         self.receive_packets()
             .context("Failed to read input video packets")?;
@@ -126,6 +186,58 @@ pub struct SourceVideo {
     video_file: PathBuf,
     ictx: format::context::Input,
     video_stream_index: usize,
+    // Set once up front for `from_reader` sources, since those can't be rewound to
+    // re-derive properties from a fresh decoder the way the file-backed path does.
+    established_properties: Option<VideoProperties>,
+    // Only set for `from_reader` sources: owns the custom AVIOContext (and the boxed
+    // reader it calls back into) for as long as `ictx` is open.
+    reader_io: Option<ReaderIo>,
+}
+
+// Size of the ffmpeg-managed buffer used to pull data from a `Read` source.
+const AVIO_BUFFER_SIZE: usize = 32 * 1024;
+// -EIO, hardcoded rather than pulling in libc for one errno constant.
+const AVERROR_EIO: c_int = -5;
+
+// Bridges a boxed `Read` into ffmpeg's AVIOContext read callback convention, so
+// `SourceVideo::from_reader` can decode a pipe or socket as well as a file.
+struct ReaderSource(Box<dyn Read + Send>);
+
+// Safety: the `ReaderSource` this points at is only ever touched from `read_packet`,
+// which ffmpeg calls synchronously from whichever thread drives `ictx`/`packets()`.
+unsafe extern "C" fn read_packet(opaque: *mut c_void, buf: *mut u8, buf_size: c_int) -> c_int {
+    let source = unsafe { &mut *(opaque as *mut ReaderSource) };
+    let out = unsafe { std::slice::from_raw_parts_mut(buf, buf_size as usize) };
+    match source.0.read(out) {
+        Ok(0) => ffmpeg::ffi::AVERROR_EOF,
+        Ok(n) => n as c_int,
+        Err(_) => AVERROR_EIO,
+    }
+}
+
+// Owns the pieces of a `from_reader` source that ffmpeg's own `avformat_close_input`
+// won't free for us, since AVFMT_FLAG_CUSTOM_IO tells it to leave `pb` alone.
+struct ReaderIo {
+    avio_ctx: *mut ffmpeg::ffi::AVIOContext,
+    source: *mut ReaderSource,
+}
+
+// Safety: `avio_ctx`/`source` are only accessed by ffmpeg internals (via the callback
+// above) and by this struct's own Drop impl, never concurrently.
+unsafe impl Send for ReaderIo {}
+
+impl Drop for ReaderIo {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.avio_ctx.is_null() {
+                ffmpeg::ffi::av_free((*self.avio_ctx).buffer as *mut c_void);
+                ffmpeg::ffi::avio_context_free(&mut self.avio_ctx);
+            }
+            if !self.source.is_null() {
+                drop(Box::from_raw(self.source));
+            }
+        }
+    }
 }
 
 // It's hard to borrow the source ffmpeg Video struct for each encoding session, as
@@ -137,15 +249,154 @@ pub struct SourceVideo {
 pub struct VideoProperties {
     out_height: u32,
     out_width: u32,
+    qcamera_width: u32,
+    qcamera_height: u32,
     format: format::Pixel,
     time_base: Option<Rational>,
     color_space: ffmpeg::color::Space,
     color_range: ffmpeg::color::Range,
+    color_primaries: ffmpeg::color::Primaries,
+    color_transfer: ffmpeg::color::TransferCharacteristic,
+    // Nanoseconds between output frames at the configured target FPS.
+    target_frame_ns: i64,
+}
+
+impl VideoProperties {
+    // True for transfer characteristics that need tone-mapping down to SDR before
+    // they can be encoded as an 8-bit JPEG thumbnail.
+    pub fn is_hdr(&self) -> bool {
+        use ffmpeg::color::TransferCharacteristic as Xfer;
+        matches!(self.color_transfer, Xfer::SMPTE2084 | Xfer::ARIB_STD_B67)
+    }
+
+    // Properties for encoding the low-res qcamera rendition instead of the full-res
+    // stream: same everything, except output dimensions.
+    pub fn qcamera_variant(&self) -> VideoProperties {
+        VideoProperties {
+            out_width: self.qcamera_width,
+            out_height: self.qcamera_height,
+            ..self.clone()
+        }
+    }
 }
 
 pub struct SourceFrame {
     pub frame: frame::Video,
     pub ts_ns: i64,
+    pub is_hdr: bool,
+    // Whether SceneDetector flagged this frame as a scene cut. The frame's kind is
+    // already set to a keyframe when this is true (see SourceFrameIterator::next),
+    // this is exposed too so callers can drive thumbnail placement off the same cut.
+    pub is_scene_cut: bool,
+    // The same frame, downscaled to qcamera resolution by a second filter chain run
+    // off the same decode, so producing both renditions doesn't double decode cost.
+    pub qcamera_frame: frame::Video,
+}
+
+// Side of the downscaled luma grid used for scene-cut detection.
+const SCENE_GRID: usize = 32;
+// Flag a cut when the frame-to-frame luma difference exceeds mean + K * stddev.
+const SCENE_CUT_K: f64 = 3.0;
+// ...or this fixed floor, whichever is higher, so a silent run of near-identical
+// frames doesn't have its variance shrink to the point that every frame is a "cut".
+const SCENE_CUT_FLOOR: f64 = 0.02;
+// Minimum number of frames between cuts, to avoid flicker bursts re-triggering.
+const MIN_FRAMES_BETWEEN_CUTS: u32 = 5;
+
+// Lightweight scene-change detector driven off a downscaled copy of each frame's
+// luma plane. Maintains a running (Welford) mean/variance of the frame-to-frame
+// difference so the cut threshold adapts to how noisy a given source is.
+pub struct SceneDetector {
+    prev_grid: Option<Vec<f64>>,
+    mean: f64,
+    m2: f64,
+    count: u64,
+    frames_since_cut: u32,
+}
+
+impl SceneDetector {
+    pub fn new() -> Self {
+        Self {
+            prev_grid: None,
+            mean: 0.0,
+            m2: 0.0,
+            count: 0,
+            frames_since_cut: MIN_FRAMES_BETWEEN_CUTS,
+        }
+    }
+
+    // Downscale the luma plane to a SCENE_GRID x SCENE_GRID grid of [0.0, 1.0] values,
+    // by averaging each block of source pixels that falls into a grid cell.
+    fn downscale_luma(frame: &frame::Video) -> Vec<f64> {
+        let width = frame.width() as usize;
+        let height = frame.height() as usize;
+        let stride = frame.stride(0);
+        let data = frame.data(0);
+
+        let mut sums = vec![0f64; SCENE_GRID * SCENE_GRID];
+        let mut counts = vec![0u32; SCENE_GRID * SCENE_GRID];
+
+        for y in 0..height {
+            let grid_y = (y * SCENE_GRID) / height.max(1);
+            let row = &data[y * stride..y * stride + width];
+            for (x, &pixel) in row.iter().enumerate() {
+                let grid_x = (x * SCENE_GRID) / width.max(1);
+                let idx = grid_y * SCENE_GRID + grid_x;
+                sums[idx] += pixel as f64;
+                counts[idx] += 1;
+            }
+        }
+
+        for (sum, count) in sums.iter_mut().zip(counts.iter()) {
+            if *count > 0 {
+                *sum = (*sum / *count as f64) / 255.0;
+            }
+        }
+
+        sums
+    }
+
+    // Returns true if `frame` should be treated as a scene cut. Always true for the
+    // very first frame seen.
+    pub fn is_cut(&mut self, frame: &frame::Video) -> bool {
+        let grid = Self::downscale_luma(frame);
+
+        let is_cut = match &self.prev_grid {
+            None => true,
+            Some(prev) => {
+                let diff = grid
+                    .iter()
+                    .zip(prev.iter())
+                    .map(|(a, b)| (a - b).abs())
+                    .sum::<f64>()
+                    / grid.len() as f64;
+
+                // Welford's online mean/variance update
+                self.count += 1;
+                let delta = diff - self.mean;
+                self.mean += delta / self.count as f64;
+                self.m2 += delta * (diff - self.mean);
+                let stddev = if self.count > 1 {
+                    (self.m2 / (self.count - 1) as f64).sqrt()
+                } else {
+                    0.0
+                };
+
+                let threshold = (self.mean + SCENE_CUT_K * stddev).max(SCENE_CUT_FLOOR);
+                diff > threshold && self.frames_since_cut >= MIN_FRAMES_BETWEEN_CUTS
+            }
+        };
+
+        self.prev_grid = Some(grid);
+        self.frames_since_cut = if is_cut { 0 } else { self.frames_since_cut + 1 };
+        is_cut
+    }
+}
+
+impl Default for SceneDetector {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SourceVideo {
@@ -163,9 +414,98 @@ impl SourceVideo {
             ictx,
             video_stream_index,
             video_file: video_file.to_path_buf(),
+            established_properties: None,
+            reader_io: None,
         })
     }
 
+    // Decode from an arbitrary `Read` (e.g. a dashcam stream piped over a socket)
+    // instead of a seekable file, via a custom AVIOContext backed by `reader`.
+    //
+    // ffmpeg-next only opens inputs by path, so this drops to the raw FFI bindings
+    // it's built on for the bit that's actually missing. Because a `Read` can't be
+    // rewound, `properties()` can't safely reopen a fresh decoder on demand the way
+    // the file-backed path does, so it's established once here instead and cached.
+    pub fn from_reader<R: Read + Send + 'static>(
+        reader: R,
+        config: &EncodeConfig,
+    ) -> Result<Self> {
+        use ffmpeg::ffi;
+
+        let source = Box::into_raw(Box::new(ReaderSource(Box::new(reader))));
+        let mut avio_ctx: *mut ffi::AVIOContext = ptr::null_mut();
+
+        let ictx = unsafe {
+            let buffer = ffi::av_malloc(AVIO_BUFFER_SIZE) as *mut u8;
+            if buffer.is_null() {
+                drop(Box::from_raw(source));
+                anyhow::bail!("Failed to allocate AVIO buffer");
+            }
+
+            avio_ctx = ffi::avio_alloc_context(
+                buffer,
+                AVIO_BUFFER_SIZE as c_int,
+                0, // read-only
+                source as *mut c_void,
+                Some(read_packet),
+                None,
+                None,
+            );
+            if avio_ctx.is_null() {
+                ffi::av_free(buffer as *mut c_void);
+                drop(Box::from_raw(source));
+                anyhow::bail!("Failed to allocate AVIOContext");
+            }
+
+            let mut fmt_ctx = ffi::avformat_alloc_context();
+            if fmt_ctx.is_null() {
+                ffi::av_free(buffer as *mut c_void);
+                ffi::avio_context_free(&mut avio_ctx);
+                drop(Box::from_raw(source));
+                anyhow::bail!("Failed to allocate AVFormatContext");
+            }
+            (*fmt_ctx).pb = avio_ctx;
+            (*fmt_ctx).flags |= ffi::AVFMT_FLAG_CUSTOM_IO as i32;
+
+            let open_result =
+                ffi::avformat_open_input(&mut fmt_ctx, ptr::null(), ptr::null_mut(), ptr::null_mut());
+            if open_result < 0 {
+                ffi::av_free(buffer as *mut c_void);
+                ffi::avio_context_free(&mut avio_ctx);
+                drop(Box::from_raw(source));
+                anyhow::bail!("Failed to open piped video stream (error {open_result})");
+            }
+
+            if ffi::avformat_find_stream_info(fmt_ctx, ptr::null_mut()) < 0 {
+                ffi::avformat_close_input(&mut fmt_ctx);
+                ffi::av_free(buffer as *mut c_void);
+                ffi::avio_context_free(&mut avio_ctx);
+                drop(Box::from_raw(source));
+                anyhow::bail!("Failed to read stream info from piped video");
+            }
+
+            format::context::Input::wrap(fmt_ctx)
+        };
+
+        let input = ictx
+            .streams()
+            .best(media::Type::Video)
+            .ok_or(ffmpeg::Error::StreamNotFound)
+            .context("Piped video stream contained no video streams")?;
+        let video_stream_index = input.index();
+
+        let mut source_video = Self {
+            ictx,
+            video_stream_index,
+            video_file: PathBuf::from("<stream>"),
+            established_properties: None,
+            reader_io: Some(ReaderIo { avio_ctx, source }),
+        };
+        let properties = source_video.properties(config, None)?;
+        source_video.established_properties = Some(properties);
+        Ok(source_video)
+    }
+
     pub fn video_decoder(&self) -> Result<decoder::Video> {
         self.ictx
             .streams()
@@ -182,19 +522,33 @@ impl SourceVideo {
 
     // Didn't have any luck implementing IntoIter for this, but this is kind of better
     // as more flexible
-    pub fn video_frames(&mut self) -> Result<SourceFrameIterator<'_>> {
+    pub fn video_frames(&mut self, config: &EncodeConfig) -> Result<SourceFrameIterator<'_>> {
         let decoder = self.video_decoder()?;
-        let props = self.properties()?;
-
-        let mut filter_spec = format!("scale={}:{}", props.out_width, props.out_height);
+        let props = self.properties(config, None)?;
 
         let rotate = self.display_rotation()?;
-        if rotate != 0 {
-            filter_spec = format!("{},rotate={}*PI/180", filter_spec, rotate);
-        }
-        eprintln!("Filter spec: {}", filter_spec);
+        let rotate_spec = if rotate != 0 {
+            format!(",rotate={}*PI/180", rotate)
+        } else {
+            String::new()
+        };
 
+        let filter_spec = format!(
+            "scale={}:{}{}",
+            props.out_width, props.out_height, rotate_spec
+        );
+        eprintln!("Filter spec: {}", filter_spec);
         let filter_graph = FilterGraph::new(&decoder, &filter_spec)?;
+
+        // Same decode, second scale chain: produces the small qcamera preview
+        // rendition alongside the full-res stream without decoding twice.
+        let qcamera_filter_spec = format!(
+            "scale={}:{}{}",
+            props.qcamera_width, props.qcamera_height, rotate_spec
+        );
+        eprintln!("qcamera filter spec: {}", qcamera_filter_spec);
+        let qcamera_filter_graph = FilterGraph::new(&decoder, &qcamera_filter_spec)?;
+
         let packets = self.ictx.packets();
 
         Ok(SourceFrameIterator {
@@ -203,6 +557,10 @@ impl SourceVideo {
             video_stream_index: self.video_stream_index,
             next_frame_ts: 0,
             filter_graph,
+            qcamera_filter_graph,
+            is_hdr: props.is_hdr(),
+            target_frame_ns: props.target_frame_ns,
+            scene_detector: SceneDetector::new(),
         })
     }
 
@@ -215,25 +573,41 @@ impl SourceVideo {
         Ok(stream.display_rotation() as i32)
     }
 
-    pub fn properties(&self) -> Result<VideoProperties> {
+    // `color_transfer_override` lets the caller correct a source stream that mislabels
+    // its own transfer function (a common problem in the wild); defaults to whatever
+    // the decoder reports.
+    pub fn properties(
+        &self,
+        config: &EncodeConfig,
+        color_transfer_override: Option<ffmpeg::color::TransferCharacteristic>,
+    ) -> Result<VideoProperties> {
+        if let Some(established) = &self.established_properties {
+            let mut properties = established.clone();
+            if let Some(color_transfer) = color_transfer_override {
+                properties.color_transfer = color_transfer;
+            }
+            return Ok(properties);
+        }
+
         let decoder = self.video_decoder()?;
 
         let in_width = decoder.width();
         let in_height = decoder.height();
-        let out_width = if in_width > VIDEO_MAX_WIDTH {
-            VIDEO_MAX_WIDTH
-        } else {
-            in_width
-        };
-        let out_height = out_width * in_height / in_width;
+        let (out_width, out_height) = config.video_size.resolve(in_width, in_height);
+        let (qcamera_width, qcamera_height) = config.qcamera_size.resolve(in_width, in_height);
 
         Ok(VideoProperties {
             out_width,
             out_height,
+            qcamera_width,
+            qcamera_height,
             format: decoder.format(),
             time_base: decoder.time_base(),
             color_space: decoder.color_space(),
             color_range: decoder.color_range(),
+            color_primaries: decoder.color_primaries(),
+            color_transfer: color_transfer_override.unwrap_or(decoder.color_transfer_characteristic()),
+            target_frame_ns: 1_000_000_000i64 / config.target_fps as i64,
         })
     }
 }
@@ -243,7 +617,11 @@ pub struct SourceFrameIterator<'a> {
     packets: format::context::input::PacketIter<'a>,
     video_stream_index: usize,
     filter_graph: FilterGraph,
+    qcamera_filter_graph: FilterGraph,
     next_frame_ts: i64,
+    target_frame_ns: i64,
+    is_hdr: bool,
+    scene_detector: SceneDetector,
 }
 
 impl<'a> Iterator for SourceFrameIterator<'a> {
@@ -264,17 +642,37 @@ impl<'a> Iterator for SourceFrameIterator<'a> {
                     if decoder.receive_frame(&mut frame).is_ok() {
                         let ts_ns = frame.pts().unwrap() * timebase_ns;
                         // Drop frames as needed to meet the target FPS rate
-                        if ts_ns >= self.next_frame_ts + TARGET_FRAME_NS {
+                        if ts_ns >= self.next_frame_ts + self.target_frame_ns {
                             self.next_frame_ts = if self.next_frame_ts == 0 {
-                                ts_ns + TARGET_FRAME_NS
+                                ts_ns + self.target_frame_ns
                             } else {
-                                self.next_frame_ts + TARGET_FRAME_NS
+                                self.next_frame_ts + self.target_frame_ns
                             };
-                            frame.set_kind(ffmpeg::picture::Type::None);
+                            let mut qcamera_frame = frame.clone();
                             self.filter_graph
                                 .filter_frame(&mut frame)
                                 .expect("Failed to filter frame");
-                            return Some(Self::Item { frame, ts_ns });
+                            self.qcamera_filter_graph
+                                .filter_frame(&mut qcamera_frame)
+                                .expect("Failed to filter qcamera frame");
+
+                            // Force a keyframe at scene cuts (enabling fast seeks in
+                            // Cabana) and otherwise leave the choice to the encoder.
+                            let is_scene_cut = self.scene_detector.is_cut(&frame);
+                            frame.set_kind(if is_scene_cut {
+                                ffmpeg::picture::Type::I
+                            } else {
+                                ffmpeg::picture::Type::None
+                            });
+                            qcamera_frame.set_kind(frame.kind());
+
+                            return Some(Self::Item {
+                                frame,
+                                ts_ns,
+                                is_hdr: self.is_hdr,
+                                is_scene_cut,
+                                qcamera_frame,
+                            });
                         }
                     }
                 }
@@ -292,19 +690,32 @@ impl<'a> Iterator for SourceFrameIterator<'a> {
 }
 
 impl SourceFrame {
-    pub fn encode_jpeg(&self) -> Vec<u8> {
+    pub fn encode_jpeg(&self, config: &EncodeConfig) -> Vec<u8> {
+        // JPEGs are SDR 8-bit, so an HDR frame needs to be tone-mapped down to BT.709
+        // first, rather than just letting the swscaler clip the highlights.
+        let tonemapped;
+        let source_frame = if self.is_hdr {
+            tonemapped = self
+                .tonemap_to_sdr()
+                .expect("Failed to tone-map HDR frame for thumbnail");
+            &tonemapped
+        } else {
+            &self.frame
+        };
+
         // JPEG scaler context takes output of the filter graph pipeline as
         // input. Uses a simple swscaler context rather than a more complex
         // av_filter pipeline.
         //
         // Making a new scaler context for each JPEG may seem wasteful, but none
         // of this code shows up at all in performance profiling...
-        let jpeg_width = JPEG_MAX_WIDTH.min(self.frame.width());
-        let jpeg_height = jpeg_width * self.frame.height() / self.frame.width();
+        let (jpeg_width, jpeg_height) = config
+            .thumbnail_size
+            .resolve(source_frame.width(), source_frame.height());
         let mut scaler = scaling::Context::get(
-            self.frame.format(),
-            self.frame.width(),
-            self.frame.height(),
+            source_frame.format(),
+            source_frame.width(),
+            source_frame.height(),
             format::Pixel::RGB24,
             jpeg_width,
             jpeg_height,
@@ -314,12 +725,12 @@ impl SourceFrame {
 
         let mut rgb_frame = frame::Video::empty();
         scaler
-            .run(&self.frame, &mut rgb_frame)
+            .run(source_frame, &mut rgb_frame)
             .expect("Failed to scale video frame for JPEG");
 
         let mut res = vec![];
 
-        let encoder = jpeg_encoder::Encoder::new(&mut res, JPEG_QUALITY);
+        let encoder = jpeg_encoder::Encoder::new(&mut res, config.jpeg_quality);
 
         encoder
             .encode(
@@ -332,6 +743,19 @@ impl SourceFrame {
 
         res
     }
+
+    // Tone-maps a PQ/HLG HDR frame down to an 8-bit BT.709 SDR frame, using the
+    // Hable operator. Only used for thumbnail generation; the full HEVC stream
+    // keeps its native HDR color space (see SegmentVideoEncoder).
+    fn tonemap_to_sdr(&self) -> Result<frame::Video> {
+        const TONEMAP_FILTER: &str = "zscale=transfer=linear:npl=100,tonemap=hable:desat=0,\
+            zscale=transfer=bt709:matrix=bt709:range=tv,format=yuv420p";
+
+        let mut filter_graph = FilterGraph::new_for_frame(&self.frame, TONEMAP_FILTER)?;
+        let mut frame = self.frame.clone();
+        filter_graph.filter_frame(&mut frame)?;
+        Ok(frame)
+    }
 }
 
 impl PartialEq for SourceFrame {
@@ -394,6 +818,43 @@ impl FilterGraph {
         Ok(FilterGraph { graph })
     }
 
+    // Like `new`, but derives the source pad args directly from a decoded frame
+    // rather than an open decoder. Used for one-off post-processing (e.g. HDR
+    // tone-mapping for thumbnails) where a frame's output pixel format may differ
+    // from its input, so the sink pixel format is left unconstrained.
+    fn new_for_frame(frame: &frame::Video, filter_spec: &str) -> Result<Self> {
+        let buffer_src = filter::find("buffer").context("can't find src")?;
+        let buffer_sink = filter::find("buffersink").context("can't find sink")?;
+        let mut graph = filter::graph::Graph::new();
+
+        let src_args = format!(
+            "video_size={}x{}:pix_fmt={}:time_base=1/1:pixel_aspect=1/1",
+            frame.width(),
+            frame.height(),
+            <Pixel as Into<AVPixelFormat>>::into(frame.format()) as i32,
+        );
+
+        let mut buffer_src_ctx = graph
+            .add(&buffer_src, Self::IN, &src_args)
+            .with_context(|| format!("Failed to add src {}", src_args))?;
+        buffer_src_ctx.set_pixel_format(frame.format());
+
+        graph
+            .add(&buffer_sink, Self::OUT, "")
+            .context("Failed to add sink")?;
+
+        graph
+            .output(Self::IN, 0)
+            .context("Failed to allocate output")?
+            .input(Self::OUT, 0)
+            .context("Failed to allocate input")?
+            .parse(filter_spec)
+            .context("Failed to parse filter spec")?;
+        graph.validate().context("Filter graph not valid")?;
+
+        Ok(FilterGraph { graph })
+    }
+
     fn filter_frame(&mut self, frame: &mut frame::Video) -> Result<()> {
         let mut src_ctx = self.graph.get(Self::IN).unwrap();
         let mut src = src_ctx.source();